@@ -10,7 +10,7 @@ const FILE: &str = "./examples/data/hydrate.postcard";
 fn main() -> Result<(), ()> {
     let path = PathBuf::from_str(FILE).unwrap();
     {
-        let mut perds = Perds::new(HashMap::new(), Strategy::Stream, path.clone()).unwrap();
+        let mut perds = Perds::<&str, &str>::new(HashMap::new(), Strategy::Stream, path.clone()).unwrap();
 
         let val = perds.insert("abc", "fed").unwrap();
         assert_eq!(val, None);
@@ -22,7 +22,8 @@ fn main() -> Result<(), ()> {
         perds.insert("Hello", "World!").unwrap();
     }
 
-    let perds: Perds<String, String> = Perds::from_file(Strategy::Stream, path.clone()).unwrap();
+    let (perds, _report): (Perds<String, String>, _) =
+        Perds::from_file(Strategy::Stream, path.clone()).unwrap();
 
     println!("Hello, {}", perds.get(&"Hello".to_string()).unwrap());
 
@@ -1,6 +1,6 @@
 #![warn(
     missing_docs,
-    clippy::overflow_check_conditional,
+    clippy::panicking_overflow_checks,
     clippy::perf,
     clippy::needless_lifetimes
 )]
@@ -24,16 +24,229 @@ use std::{
     fs::File,
     hash::Hash,
     io::{BufWriter, Read, Write},
+    marker::PhantomData,
     path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant},
 };
 
 /// The persistent container for a std library collection type
+///
+/// The `C` type parameter selects the serialization [Codec] used for the
+/// append only log and defaults to [Postcard].
 #[derive(Debug)]
-pub struct Perds<K, V> {
+pub struct Perds<K, V, C = Postcard> {
     strategy: Strategy,
     inner: HashMap<K, V>,
     writer: BufWriter<File>,
     path: PathBuf,
+    /// Number of records currently present in the append only log, including
+    /// stale records that have since been overwritten or deleted. Used to
+    /// drive the [Strategy::Compact] auto-trigger in O(1).
+    records: usize,
+    /// Cipher state for an encrypted-at-rest instance, or [None] for plaintext
+    enc: Option<Crypto>,
+    /// Advisory exclusive lock on the backing file, held for the lifetime of
+    /// this instance and released on [Drop]
+    lock: File,
+    /// Group-commit writer thread for [Strategy::Background], else [None]
+    bg: Option<BackgroundWriter>,
+    _codec: PhantomData<C>,
+}
+
+impl<K, V, C> Drop for Perds<K, V, C> {
+    fn drop(&mut self) {
+        // Drain and join the background writer first so no committed op is lost
+        if let Some(mut bg) = self.bg.take() {
+            bg.shutdown();
+        }
+        // Best effort: flush any buffered writes and release the advisory lock
+        let _ = self.writer.flush();
+        let _ = fs2::FileExt::unlock(&self.lock);
+    }
+}
+
+/// Streaming cipher state for an encrypted [Perds]
+///
+/// The append only file is kept in sync with the keystream by encrypting every
+/// record under a keystream position equal to its absolute byte offset in the
+/// file. Re-opening an existing file therefore only needs the caller's key and
+/// the nonce stored in the header to resume appending in sync.
+#[derive(Debug, Clone)]
+struct Crypto {
+    key: [u8; 32],
+    nonce: [u8; 24],
+    /// Absolute file offset at which the next record will be written
+    offset: u64,
+}
+
+/// Encrypt or decrypt `buf` in place with XChaCha20 seeked to `offset`
+///
+/// The cipher is symmetric, so the same call both encrypts on append and
+/// decrypts on hydration. `offset` is the absolute byte position of `buf` in
+/// the file, which keeps appends in sync with the keystream across re-opens.
+fn xchacha_apply(key: &[u8; 32], nonce: &[u8; 24], offset: u64, buf: &mut [u8]) {
+    use chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+    let mut cipher = chacha20::XChaCha20::new(key.into(), nonce.into());
+    cipher.seek(offset);
+    cipher.apply_keystream(buf);
+}
+
+/// Observable counters for a [Strategy::Background] writer
+///
+/// A snapshot is returned from [Perds::background_stats].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct BackgroundStats {
+    /// Records handed to the writer thread
+    pub queued: u64,
+    /// Records written and flushed to the file by the writer thread (no fsync)
+    pub committed: u64,
+    /// Wall-clock duration of the most recent group commit
+    pub last_flush: Duration,
+}
+
+/// A message sent to the background writer thread
+enum WriteMsg {
+    /// A serialized (and, if encrypted, already ciphered) record to append
+    Record(Vec<u8>),
+    /// Flush all pending records, then acknowledge on the channel
+    Flush(mpsc::Sender<()>),
+}
+
+/// Handle to a [Strategy::Background] writer thread
+///
+/// Owns the channel to the thread and its join handle. [shutdown](BackgroundWriter::shutdown)
+/// (called from [Perds::drop]) closes the channel and joins the thread so the
+/// queue is fully drained.
+#[derive(Debug)]
+struct BackgroundWriter {
+    tx: Option<mpsc::Sender<WriteMsg>>,
+    handle: Option<JoinHandle<()>>,
+    stats: Arc<Mutex<BackgroundStats>>,
+}
+
+impl BackgroundWriter {
+    /// Enqueue a record for the writer thread, counting it as queued
+    fn enqueue(&self, bytes: Vec<u8>) {
+        if let Some(tx) = &self.tx {
+            if let Ok(mut stats) = self.stats.lock() {
+                stats.queued += 1;
+            }
+            let _ = tx.send(WriteMsg::Record(bytes));
+        }
+    }
+
+    /// Ask the thread to flush and block until it acknowledges
+    fn flush(&self) {
+        if let Some(tx) = &self.tx {
+            let (ack, ackrx) = mpsc::channel();
+            if tx.send(WriteMsg::Flush(ack)).is_ok() {
+                let _ = ackrx.recv();
+            }
+        }
+    }
+
+    /// Close the channel and join the thread, draining all pending records
+    fn shutdown(&mut self) {
+        self.tx.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+
+    /// Snapshot the current counters
+    fn stats(&self) -> BackgroundStats {
+        self.stats.lock().map(|s| s.clone()).unwrap_or_default()
+    }
+}
+
+/// The writer thread loop: coalesce records and group-commit them
+///
+/// A commit happens when `max_batch` records have accumulated, when `max_delay`
+/// has elapsed since the first pending record, on an explicit flush request, or
+/// when the channel is closed (drain on shutdown).
+fn background_run(
+    rx: mpsc::Receiver<WriteMsg>,
+    mut writer: BufWriter<File>,
+    max_batch: usize,
+    max_delay: Duration,
+    stats: Arc<Mutex<BackgroundStats>>,
+) {
+    let mut pending: Vec<u8> = Vec::new();
+    let mut count = 0usize;
+    let mut deadline: Option<Instant> = None;
+
+    let commit = |writer: &mut BufWriter<File>, pending: &mut Vec<u8>, count: &mut usize| {
+        if *count == 0 {
+            return;
+        }
+        let started = Instant::now();
+        let _ = writer.write_all(pending);
+        let _ = writer.flush();
+        if let Ok(mut stats) = stats.lock() {
+            stats.committed += *count as u64;
+            stats.last_flush = started.elapsed();
+        }
+        pending.clear();
+        *count = 0;
+    };
+
+    loop {
+        let msg = match deadline {
+            Some(d) => rx.recv_timeout(d.saturating_duration_since(Instant::now())),
+            None => rx.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+        };
+        match msg {
+            Ok(WriteMsg::Record(bytes)) => {
+                pending.extend_from_slice(&bytes);
+                count += 1;
+                if deadline.is_none() {
+                    deadline = Some(Instant::now() + max_delay);
+                }
+                if count >= max_batch {
+                    commit(&mut writer, &mut pending, &mut count);
+                    deadline = None;
+                }
+            }
+            Ok(WriteMsg::Flush(ack)) => {
+                commit(&mut writer, &mut pending, &mut count);
+                deadline = None;
+                let _ = ack.send(());
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                commit(&mut writer, &mut pending, &mut count);
+                deadline = None;
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                commit(&mut writer, &mut pending, &mut count);
+                break;
+            }
+        }
+    }
+}
+
+/// Spawn a [BackgroundWriter] when the strategy calls for one, else [None]
+fn maybe_spawn_bg(strategy: &Strategy, path: &Path) -> Result<Option<BackgroundWriter>, Error> {
+    if let Strategy::Background {
+        max_batch,
+        max_delay,
+    } = *strategy
+    {
+        let writer = BufWriter::new(File::options().append(true).create(true).open(path)?);
+        let stats = Arc::new(Mutex::new(BackgroundStats::default()));
+        let thread_stats = Arc::clone(&stats);
+        let (tx, rx) = mpsc::channel();
+        let handle =
+            std::thread::spawn(move || background_run(rx, writer, max_batch, max_delay, thread_stats));
+        Ok(Some(BackgroundWriter {
+            tx: Some(tx),
+            handle: Some(handle),
+            stats,
+        }))
+    } else {
+        Ok(None)
+    }
 }
 
 /// The persistence strategy for a Perds instance
@@ -43,6 +256,32 @@ pub enum Strategy {
     Stream,
     /// Flush only when [flush](Perds::flush()) is explicitly called
     Manual,
+    /// Flush on every update and [compact](Perds::compact()) the log
+    /// automatically once it grows past `max_records` records
+    ///
+    /// The append only log never shrinks on its own: inserting the same key
+    /// repeatedly leaves one stale record behind every time. This variant
+    /// rewrites the log down to one record per live entry once the total
+    /// record count exceeds `max_records`.
+    Compact {
+        /// The record count above which a compaction is triggered
+        max_records: usize,
+    },
+    /// Hand records to a dedicated writer thread that coalesces them into a
+    /// single `write_all`+`flush` (group commit)
+    ///
+    /// [insert](Perds::insert)/[remove](Perds::remove) update the in-memory map
+    /// and enqueue the serialized record without blocking on disk. The writer
+    /// thread flushes once `max_batch` records have accumulated or `max_delay`
+    /// has elapsed, whichever comes first. [flush](Perds::flush) blocks until
+    /// the queue is drained; [Drop] drains the queue and joins the thread, so
+    /// no committed op is lost.
+    Background {
+        /// Flush once this many records are queued
+        max_batch: usize,
+        /// Flush at latest this long after the first queued record
+        max_delay: Duration,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -51,6 +290,276 @@ enum Operation {
     Delete,
 }
 
+/// A single decoded log record produced by [Codec::decode_record]
+pub enum Entry<K, V> {
+    /// An insert of a key/value pair
+    Insert(K, V),
+    /// A deletion of a key
+    Delete(K),
+}
+
+/// Encode and decode `(Operation, K, V)` log records
+///
+/// [Perds] is generic over this trait so callers can persist with whichever
+/// serialization format their types already derive. The chosen codec is
+/// recorded in the file header via [Codec::TAG], so opening a file with the
+/// wrong codec fails with [Error::UnsupportedCodec] rather than silently
+/// decoding garbage. Tag `2` is reserved for a future zero-copy `rkyv` codec
+/// that can borrow from validated archived bytes instead of deserializing.
+pub trait Codec {
+    /// Header tag byte identifying this codec on disk
+    const TAG: u8;
+
+    /// Encode an insert of `k` -> `v` into a record payload
+    fn encode_insert<K: Serialize, V: Serialize>(k: &K, v: &V) -> Result<Vec<u8>, Error>;
+
+    /// Encode a deletion of `k` into a record payload
+    fn encode_delete<K: Serialize>(k: &K) -> Result<Vec<u8>, Error>;
+
+    /// Decode a single record payload back into an [Entry]
+    fn decode_record<K: DeserializeOwned, V: DeserializeOwned>(
+        bytes: &[u8],
+    ) -> Result<Entry<K, V>, Error>;
+}
+
+/// The default [Codec], backed by [postcard]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Postcard;
+
+impl Codec for Postcard {
+    const TAG: u8 = 0;
+
+    fn encode_insert<K: Serialize, V: Serialize>(k: &K, v: &V) -> Result<Vec<u8>, Error> {
+        Ok(postcard::to_stdvec(&(Operation::Insert, k, v))?)
+    }
+
+    fn encode_delete<K: Serialize>(k: &K) -> Result<Vec<u8>, Error> {
+        Ok(postcard::to_stdvec(&(Operation::Delete, k))?)
+    }
+
+    fn decode_record<K: DeserializeOwned, V: DeserializeOwned>(
+        bytes: &[u8],
+    ) -> Result<Entry<K, V>, Error> {
+        let (op, rest) = postcard::take_from_bytes::<Operation>(bytes)?;
+        let (k, rest) = postcard::take_from_bytes::<K>(rest)?;
+        Ok(match op {
+            Operation::Delete => Entry::Delete(k),
+            Operation::Insert => {
+                let (v, _rest) = postcard::take_from_bytes::<V>(rest)?;
+                Entry::Insert(k, v)
+            }
+        })
+    }
+}
+
+/// A [Codec] backed by [bincode] for types already using a bincode derive setup
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Bincode;
+
+impl Codec for Bincode {
+    const TAG: u8 = 1;
+
+    fn encode_insert<K: Serialize, V: Serialize>(k: &K, v: &V) -> Result<Vec<u8>, Error> {
+        Ok(bincode::serialize(&(Operation::Insert, k, v))?)
+    }
+
+    fn encode_delete<K: Serialize>(k: &K) -> Result<Vec<u8>, Error> {
+        Ok(bincode::serialize(&(Operation::Delete, k))?)
+    }
+
+    fn decode_record<K: DeserializeOwned, V: DeserializeOwned>(
+        bytes: &[u8],
+    ) -> Result<Entry<K, V>, Error> {
+        // bincode serializes a tuple as its fields back to back, so we can read
+        // the operation, key and (for inserts) value sequentially
+        let mut cursor = std::io::Cursor::new(bytes);
+        let op: Operation = bincode::deserialize_from(&mut cursor)?;
+        let k: K = bincode::deserialize_from(&mut cursor)?;
+        Ok(match op {
+            Operation::Delete => Entry::Delete(k),
+            Operation::Insert => Entry::Insert(k, bincode::deserialize_from(&mut cursor)?),
+        })
+    }
+}
+
+/// What happened while hydrating a [Perds] from its append only file
+///
+/// Returned by [Perds::from_file] and [Perds::try_from_file] so callers can
+/// tell when a torn tail was detected and healed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// Number of valid records replayed into the map
+    pub records_replayed: usize,
+    /// Number of bytes truncated from a torn tail (0 when the log was intact)
+    pub bytes_truncated: u64,
+}
+
+/// Magic bytes written at offset 0 of every Perds file
+const MAGIC: [u8; 4] = *b"PRDS";
+/// The on-disk format version understood by this build
+///
+/// Version 2 added per-record CRC framing (see [frame]); version 1 used bare
+/// concatenated postcard records behind the header. [Perds::upgrade] migrates
+/// older files forward.
+const FORMAT_VERSION: u8 = 2;
+/// Length in bytes of the fixed file header (magic + version + codec)
+const HEADER_LEN: usize = 6;
+/// High bit of the header codec byte, set when the records are encrypted at rest
+///
+/// Keeping the flag in the codec byte avoids changing [HEADER_LEN] (and the
+/// nonce/record offsets derived from it) while still letting a loader reject a
+/// plaintext/encrypted mismatch before it reaches the torn-tail healing path.
+const ENCRYPTED_FLAG: u8 = 0x80;
+/// Length in bytes of the XChaCha20 nonce stored after the header in encrypted
+/// files (see [Perds::new_encrypted])
+const NONCE_LEN: usize = 24;
+
+/// Validate the fixed header at the start of a file and return its codec byte
+/// alongside the record bytes
+///
+/// The header is `[b"PRDS"][u8 format version][u8 codec byte]`, where the codec
+/// byte is the [Codec::TAG] OR-ed with [ENCRYPTED_FLAG] for encrypted files. A
+/// missing or mismatched magic yields [Error::BadMagic]; a version this build
+/// does not understand yields [Error::UnsupportedVersion]. Legacy headerless
+/// files must be migrated with [Perds::upgrade] first. The caller is responsible
+/// for checking the codec byte against the mode it opened with via [check_tag].
+fn split_header(buf: &[u8]) -> Result<(u8, &[u8]), Error> {
+    if buf.len() < HEADER_LEN || buf[..MAGIC.len()] != MAGIC {
+        return Err(Error::BadMagic);
+    }
+    let version = buf[4];
+    if version != FORMAT_VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    Ok((buf[5], &buf[HEADER_LEN..]))
+}
+
+/// Check a header codec byte against the [Codec] and encryption mode the file
+/// was opened with
+///
+/// A codec mismatch yields [Error::UnsupportedCodec]; opening an encrypted file
+/// as plaintext (or vice versa) yields [Error::WrongMode]. Both are surfaced
+/// before any record replay or torn-tail healing runs, so using the wrong
+/// loader fails cleanly instead of truncating the log.
+fn check_tag<C: Codec>(byte: u8, encrypted: bool) -> Result<(), Error> {
+    let codec = byte & !ENCRYPTED_FLAG;
+    if codec != C::TAG {
+        return Err(Error::UnsupportedCodec(codec));
+    }
+    if (byte & ENCRYPTED_FLAG != 0) != encrypted {
+        return Err(Error::WrongMode);
+    }
+    Ok(())
+}
+
+/// Acquire an advisory exclusive lock on the backing file
+///
+/// A fresh handle to `path` is opened and `flock`-style exclusively locked so a
+/// second [Perds] opening the same path (in this or another process) cannot
+/// interleave writes and corrupt the log. The returned handle must be kept
+/// alive for as long as the lock is needed; dropping it releases the lock.
+///
+/// With `timeout` of [None] the attempt is non-blocking and returns
+/// [Error::Locked] immediately if another holder exists. With a timeout the
+/// attempt is retried until the deadline before giving up with [Error::Locked].
+fn acquire_lock(path: &Path, create: bool, timeout: Option<Duration>) -> Result<File, Error> {
+    use fs2::FileExt;
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .create(create)
+        .open(path)?;
+    match timeout {
+        None => match file.try_lock_exclusive() {
+            Ok(()) => Ok(file),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Err(Error::Locked),
+            Err(e) => Err(Error::FileError(e)),
+        },
+        Some(dur) => {
+            let deadline = Instant::now() + dur;
+            loop {
+                match file.try_lock_exclusive() {
+                    Ok(()) => return Ok(file),
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        if Instant::now() >= deadline {
+                            return Err(Error::Locked);
+                        }
+                        std::thread::sleep(Duration::from_millis(10));
+                    }
+                    Err(e) => return Err(Error::FileError(e)),
+                }
+            }
+        }
+    }
+}
+
+/// Write the fixed header for the current format to `writer`, tagged with
+/// `codec` and, when `encrypted`, the [ENCRYPTED_FLAG] bit
+fn write_header<W: Write>(writer: &mut W, codec: u8, encrypted: bool) -> Result<(), Error> {
+    let byte = if encrypted { codec | ENCRYPTED_FLAG } else { codec };
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&[FORMAT_VERSION, byte])?;
+    Ok(())
+}
+
+/// Length in bytes of a record frame's fixed prefix (`[u32 len][u32 crc]`)
+const FRAME_PREFIX_LEN: usize = 8;
+
+/// Wrap a [Codec] encoded payload in a length + CRC frame
+///
+/// Each log record is framed as `[u32 length][u32 crc32 of payload][payload]`
+/// (lengths little endian) so that a torn tail from a crash mid-write can be
+/// detected and healed during hydration rather than failing the whole load.
+fn frame(payload: &[u8]) -> Vec<u8> {
+    let crc = crc32fast::hash(payload);
+    let mut out = Vec::with_capacity(FRAME_PREFIX_LEN + payload.len());
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(&crc.to_le_bytes());
+    out.extend_from_slice(payload);
+    out
+}
+
+/// Replay framed records from `records` into `map`
+///
+/// Returns the number of records replayed and the number of record bytes
+/// consumed. A frame whose length runs past the end of the buffer or whose CRC
+/// does not match is treated as a torn tail: replay stops and the consumed
+/// byte count points at the start of that frame so the caller can truncate.
+fn replay<K, V, C>(records: &[u8], map: &mut HashMap<K, V>) -> Result<(usize, usize), Error>
+where
+    K: Eq + Hash + DeserializeOwned,
+    V: DeserializeOwned,
+    C: Codec,
+{
+    let mut offset = 0;
+    let mut replayed = 0;
+    while offset < records.len() {
+        let frame = &records[offset..];
+        if frame.len() < FRAME_PREFIX_LEN {
+            break;
+        }
+        let len = u32::from_le_bytes(frame[0..4].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(frame[4..FRAME_PREFIX_LEN].try_into().unwrap());
+        let end = FRAME_PREFIX_LEN + len;
+        if frame.len() < end {
+            // The frame claims more bytes than are present: torn tail
+            break;
+        }
+        let payload = &frame[FRAME_PREFIX_LEN..end];
+        if crc32fast::hash(payload) != crc {
+            // Corrupt payload: torn tail
+            break;
+        }
+        match C::decode_record::<K, V>(payload)? {
+            Entry::Delete(k) => map.remove(&k),
+            Entry::Insert(k, v) => map.insert(k, v),
+        };
+        offset += end;
+        replayed += 1;
+    }
+    Ok((replayed, offset))
+}
+
 /// All errors related to the Perds crate
 ///
 /// These will typically wrap an inner error type
@@ -58,8 +567,25 @@ enum Operation {
 pub enum Error {
     /// Wrapper around [std::io::Error]
     FileError(std::io::Error),
-    /// Serialization/Deserialization error
-    SerError(postcard::Error),
+    /// Serialization/Deserialization error from the active [Codec]
+    SerError(Box<dyn std::error::Error + Send + Sync>),
+    /// The file did not begin with the expected `PRDS` magic
+    ///
+    /// A legacy headerless file produces this error; migrate it with
+    /// [Perds::upgrade] before opening.
+    BadMagic,
+    /// The file declared a format version this build does not understand
+    UnsupportedVersion(u8),
+    /// The file was written with a different [Codec] than it was opened with
+    ///
+    /// Holds the codec tag found in the header.
+    UnsupportedCodec(u8),
+    /// The file was opened in the wrong mode: a plaintext loader was pointed at
+    /// an encrypted file, or [from_file_encrypted](Perds::from_file_encrypted)
+    /// at a plaintext one
+    WrongMode,
+    /// Another live [Perds] (in this or another process) holds the file lock
+    Locked,
 }
 
 impl From<std::io::Error> for Error {
@@ -70,21 +596,28 @@ impl From<std::io::Error> for Error {
 
 impl From<postcard::Error> for Error {
     fn from(value: postcard::Error) -> Self {
+        Error::SerError(Box::new(value))
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(value: bincode::Error) -> Self {
         Error::SerError(value)
     }
 }
 
-impl<K, V> Perds<K, V> {
+impl<K, V, C> Perds<K, V, C> {
     /// Get the path of the append only file of this instance
     pub fn path(&self) -> &Path {
         self.path.as_path()
     }
 }
 
-impl<K, V> Perds<K, V>
+impl<K, V, C> Perds<K, V, C>
 where
     K: Eq + Hash + DeserializeOwned,
     V: DeserializeOwned,
+    C: Codec,
 {
     /// Hydrate a Perds from data in a provided file path
     ///
@@ -92,6 +625,9 @@ where
     ///
     /// * `path` - Path to the append only file we want to hydrate from
     ///
+    /// On success the [RecoveryReport] records how many frames were replayed and
+    /// whether a torn tail from a crash mid-write had to be truncated.
+    ///
     /// # Example
     ///
     /// ```
@@ -99,36 +635,134 @@ where
     ///  use std::str::FromStr;
     ///
     ///  let path = std::path::PathBuf::from_str("./examples/doc.postcard").unwrap();
-    ///  let p: Perds<String, String> = Perds::from_file(Strategy::Stream, path).unwrap();
+    ///  let (p, _report): (Perds<String, String>, _) =
+    ///      Perds::from_file(Strategy::Stream, path).unwrap();
     ///
     ///  assert_eq!(p.get(&"foo".to_string()), None);
     /// ```
-    pub fn from_file(strategy: Strategy, path: PathBuf) -> Result<Self, Error> {
-        let mut f = File::open(&path)?;
+    pub fn from_file(strategy: Strategy, path: PathBuf) -> Result<(Self, RecoveryReport), Error> {
+        let lock = acquire_lock(&path, false, None)?;
+        Self::hydrate_plain(strategy, path, lock)
+    }
+
+    /// Hydrate a Perds, waiting up to `timeout` for the file lock
+    ///
+    /// Like [from_file](Perds::from_file) but instead of failing immediately
+    /// with [Error::Locked] when another holder exists, the lock attempt is
+    /// retried until `timeout` elapses.
+    pub fn open_locked_timeout(
+        strategy: Strategy,
+        path: PathBuf,
+        timeout: Duration,
+    ) -> Result<(Self, RecoveryReport), Error> {
+        let lock = acquire_lock(&path, false, Some(timeout))?;
+        Self::hydrate_plain(strategy, path, lock)
+    }
+
+    /// Shared hydration body for [from_file](Perds::from_file) and
+    /// [open_locked_timeout](Perds::open_locked_timeout), given an acquired lock
+    fn hydrate_plain(
+        strategy: Strategy,
+        path: PathBuf,
+        lock: File,
+    ) -> Result<(Self, RecoveryReport), Error> {
         let mut buf = Vec::new();
-        f.read_to_end(&mut buf)?;
+        File::open(&path)?.read_to_end(&mut buf)?;
+        let (byte, records) = split_header(&buf)?;
+        check_tag::<C>(byte, false)?;
+        let record_len = records.len();
         let mut map = HashMap::new();
-        let mut buf = buf.as_slice();
-        while !buf.is_empty() {
-            let (op, rest) = postcard::take_from_bytes::<Operation>(buf)?;
-            buf = rest;
-            let (k, rest) = postcard::take_from_bytes::<K>(buf)?;
-            buf = rest;
-            match op {
-                Operation::Delete => map.remove(&k),
-                Operation::Insert => {
-                    let (v, rest) = postcard::take_from_bytes::<V>(buf)?;
-                    buf = rest;
-                    map.insert(k, v)
-                }
-            };
+        let (replayed, good) = replay::<K, V, C>(records, &mut map)?;
+        let bytes_truncated = (record_len - good) as u64;
+        if bytes_truncated > 0 {
+            // Heal the torn tail so subsequent appends land on a clean boundary
+            File::options()
+                .write(true)
+                .open(&path)?
+                .set_len((HEADER_LEN + good) as u64)?;
         }
-        Ok(Self {
-            strategy,
-            inner: map,
-            writer: BufWriter::new(f),
-            path,
-        })
+        let writer = BufWriter::new(File::options().append(true).open(&path)?);
+        let report = RecoveryReport {
+            records_replayed: replayed,
+            bytes_truncated,
+        };
+        let bg = maybe_spawn_bg(&strategy, &path)?;
+        Ok((
+            Self {
+                strategy,
+                inner: map,
+                writer,
+                path,
+                records: replayed,
+                enc: None,
+                lock,
+                bg,
+                _codec: PhantomData,
+            },
+            report,
+        ))
+    }
+
+    /// Hydrate an encrypted Perds written by [Perds::new_encrypted]
+    ///
+    /// The file is read whole and decrypted with `key` and the 24-byte nonce
+    /// stored in its header before the usual record replay runs. The same torn
+    /// tail recovery applies to the decrypted bytes.
+    pub fn from_file_encrypted(
+        strategy: Strategy,
+        path: PathBuf,
+        key: [u8; 32],
+    ) -> Result<(Self, RecoveryReport), Error> {
+        let lock = acquire_lock(&path, false, None)?;
+        let mut buf = Vec::new();
+        File::open(&path)?.read_to_end(&mut buf)?;
+        // Validate the header, then read the nonce that follows it
+        let (byte, _) = split_header(&buf)?;
+        check_tag::<C>(byte, true)?;
+        if buf.len() < HEADER_LEN + NONCE_LEN {
+            return Err(Error::BadMagic);
+        }
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce.copy_from_slice(&buf[HEADER_LEN..HEADER_LEN + NONCE_LEN]);
+        let record_start = HEADER_LEN + NONCE_LEN;
+        // Decrypt the record region in place; keystream position is absolute
+        xchacha_apply(&key, &nonce, record_start as u64, &mut buf[record_start..]);
+
+        let record_len = buf.len() - record_start;
+        let mut map = HashMap::new();
+        let (replayed, good) = replay::<K, V, C>(&buf[record_start..], &mut map)?;
+        let bytes_truncated = (record_len - good) as u64;
+        if bytes_truncated > 0 {
+            File::options()
+                .write(true)
+                .open(&path)?
+                .set_len((record_start + good) as u64)?;
+        }
+        let writer = BufWriter::new(File::options().append(true).open(&path)?);
+        let report = RecoveryReport {
+            records_replayed: replayed,
+            bytes_truncated,
+        };
+        let enc = Some(Crypto {
+            key,
+            nonce,
+            offset: (record_start + good) as u64,
+        });
+        let bg = maybe_spawn_bg(&strategy, &path)?;
+        Ok((
+            Self {
+                strategy,
+                inner: map,
+                writer,
+                path,
+                records: replayed,
+                enc,
+                lock,
+                bg,
+                _codec: PhantomData,
+            },
+            report,
+        ))
     }
 
     /// Hydrate a Perds from data in a provided file path
@@ -146,44 +780,133 @@ where
     ///  use std::str::FromStr;
     ///
     ///  let path = std::path::PathBuf::from_str("./examples/doc.postcard").unwrap();
-    ///  let p: Perds<String, String> = Perds::try_from_file(Strategy::Stream, path).unwrap();
+    ///  let (p, _report): (Perds<String, String>, _) =
+    ///      Perds::try_from_file(Strategy::Stream, path).unwrap();
     ///
     ///  assert_eq!(p.get(&"foo".to_string()), None);
     /// ```
-    pub fn try_from_file(strategy: Strategy, path: PathBuf) -> Result<Self, Error> {
+    pub fn try_from_file(
+        strategy: Strategy,
+        path: PathBuf,
+    ) -> Result<(Self, RecoveryReport), Error> {
+        let lock = acquire_lock(&path, false, None)?;
         let mut f = File::options().write(true).read(true).open(&path)?;
         let mut buf = Vec::new();
-        eprintln!("Here? f: {:?}", f);
         f.read_to_end(&mut buf)?;
         let mut inner = HashMap::new();
-        let mut buf = buf.as_slice();
-        while !buf.is_empty() {
-            let (op, rest) = postcard::take_from_bytes::<Operation>(buf)?;
-            buf = rest;
-            let (k, rest) = postcard::take_from_bytes::<K>(buf)?;
-            buf = rest;
+        let (byte, record_bytes) = split_header(&buf)?;
+        check_tag::<C>(byte, false)?;
+        let record_len = record_bytes.len();
+        let (replayed, good) = replay::<K, V, C>(record_bytes, &mut inner)?;
+        let bytes_truncated = (record_len - good) as u64;
+        if bytes_truncated > 0 {
+            // Heal the torn tail so subsequent appends land on a clean boundary
+            f.set_len((HEADER_LEN + good) as u64)?;
+        }
+        let writer = BufWriter::new(File::options().append(true).open(&path)?);
+        let report = RecoveryReport {
+            records_replayed: replayed,
+            bytes_truncated,
+        };
+        let bg = maybe_spawn_bg(&strategy, &path)?;
+        Ok((
+            Self {
+                strategy,
+                inner,
+                writer,
+                path,
+                records: replayed,
+                enc: None,
+                lock,
+                bg,
+                _codec: PhantomData,
+            },
+            report,
+        ))
+    }
+}
+
+impl<K, V, C> Perds<K, V, C>
+where
+    K: Hash + Eq + Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    C: Codec,
+{
+    /// Migrate an existing file in place to the current on-disk format
+    ///
+    /// A legacy headerless file (written before the versioned header existed)
+    /// or a file stamped with an older [FORMAT_VERSION] is replayed with the
+    /// decode rules appropriate to its format, then rewritten with the current
+    /// header and one `Insert` record per live entry. A file already at the
+    /// current version is left untouched.
+    ///
+    /// The rewrite goes through a sibling temp file and an atomic
+    /// [rename](std::fs::rename), so an interrupted upgrade never leaves the
+    /// original truncated.
+    pub fn upgrade(path: &Path) -> Result<(), Error> {
+        let mut buf = Vec::new();
+        File::open(path)?.read_to_end(&mut buf)?;
+
+        // Decide which bytes hold the records based on the existing header
+        let records = if buf.len() >= MAGIC.len() && buf[..MAGIC.len()] == MAGIC {
+            let version = buf.get(4).copied().unwrap_or(0);
+            if version == FORMAT_VERSION {
+                return Ok(());
+            }
+            if version > FORMAT_VERSION {
+                return Err(Error::UnsupportedVersion(version));
+            }
+            &buf[HEADER_LEN..]
+        } else {
+            // Legacy headerless file: records start at offset 0
+            buf.as_slice()
+        };
+
+        // Formats older than the current version predate both the CRC framing
+        // and pluggable codecs, so their records are bare postcard tuples
+        let mut map: HashMap<K, V> = HashMap::new();
+        let mut rest = records;
+        while !rest.is_empty() {
+            let (op, tail) = postcard::take_from_bytes::<Operation>(rest)?;
+            let (k, tail) = postcard::take_from_bytes::<K>(tail)?;
+            rest = tail;
             match op {
-                Operation::Delete => inner.remove(&k),
+                Operation::Delete => map.remove(&k),
                 Operation::Insert => {
-                    let (v, rest) = postcard::take_from_bytes::<V>(buf)?;
-                    buf = rest;
-                    inner.insert(k, v)
+                    let (v, tail) = postcard::take_from_bytes::<V>(tail)?;
+                    rest = tail;
+                    map.insert(k, v)
                 }
             };
         }
-        Ok(Self {
-            strategy,
-            inner,
-            writer: BufWriter::new(f),
-            path,
-        })
+
+        let tmp = path.with_extension("upgrade.tmp");
+        {
+            let mut writer = BufWriter::new(
+                File::options()
+                    .write(true)
+                    .truncate(true)
+                    .create(true)
+                    .open(&tmp)?,
+            );
+            write_header(&mut writer, C::TAG, false)?;
+            for (k, v) in map.iter() {
+                let cmd = frame(&C::encode_insert(k, v)?);
+                writer.write_all(cmd.as_slice())?;
+            }
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+        }
+        std::fs::rename(&tmp, path)?;
+        Ok(())
     }
 }
 
-impl<K, V> Perds<K, V>
+impl<K, V, C> Perds<K, V, C>
 where
     K: Hash + Eq + Serialize,
     V: Serialize,
+    C: Codec,
 {
     /// Instantiate a new Perds instance with a given strategy
     ///
@@ -191,6 +914,35 @@ where
     ///
     /// <div class="warning">Existing files in this path will be overwritten</div>
     pub fn new(value: HashMap<K, V>, strategy: Strategy, path: PathBuf) -> Result<Self, Error> {
+        Self::new_inner(value, strategy, path, None)
+    }
+
+    /// Instantiate a new encrypted-at-rest Perds instance
+    ///
+    /// Records are encrypted with XChaCha20 under the caller supplied `key` and
+    /// a fresh random 24-byte nonce stored in the file header, so the append
+    /// only file is never plaintext on disk. Re-open the file with
+    /// [from_file_encrypted](Perds::from_file_encrypted) and the same key.
+    ///
+    /// <div class="warning">Existing files in this path will be overwritten</div>
+    pub fn new_encrypted(
+        value: HashMap<K, V>,
+        strategy: Strategy,
+        path: PathBuf,
+        key: [u8; 32],
+    ) -> Result<Self, Error> {
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        Self::new_inner(value, strategy, path, Some((key, nonce)))
+    }
+
+    /// Shared constructor for [new](Perds::new) and [new_encrypted](Perds::new_encrypted)
+    fn new_inner(
+        value: HashMap<K, V>,
+        strategy: Strategy,
+        path: PathBuf,
+        key_nonce: Option<([u8; 32], [u8; NONCE_LEN])>,
+    ) -> Result<Self, Error> {
+        let lock = acquire_lock(&path, true, None)?;
         let mut writer = {
             let f = File::options()
                 .write(true)
@@ -199,24 +951,39 @@ where
                 .open(&path)?;
             BufWriter::new(f)
         };
-        if !value.is_empty() {
-            // TODO: Fix this horrific thing
-            let mut cmds = vec![];
-            for (k, v) in value.iter() {
-                // I know the size of the Operation but need to know the size of
-                // k and v in order to use postcard::to_slice and not have an
-                // allocation for every single operation
-                let mut pc = postcard::to_stdvec(&(Operation::Insert, k, v))?;
-                cmds.append(&mut pc)
+        write_header(&mut writer, C::TAG, key_nonce.is_some())?;
+        let mut offset = HEADER_LEN as u64;
+        let mut enc = match key_nonce {
+            Some((key, nonce)) => {
+                // The nonce lives in the header, in the clear, so the file can
+                // be re-opened with only the caller's key
+                writer.write_all(&nonce)?;
+                offset += NONCE_LEN as u64;
+                Some(Crypto { key, nonce, offset })
             }
-            writer.write_all(cmds.as_slice())?;
-            writer.flush()?;
+            None => None,
+        };
+        for (k, v) in value.iter() {
+            let mut cmd = frame(&C::encode_insert(k, v)?);
+            if let Some(c) = enc.as_mut() {
+                xchacha_apply(&c.key, &c.nonce, c.offset, &mut cmd);
+                c.offset += cmd.len() as u64;
+            }
+            writer.write_all(&cmd)?;
         }
+        writer.flush()?;
+        let records = value.len();
+        let bg = maybe_spawn_bg(&strategy, &path)?;
         Ok(Self {
             strategy,
             inner: value,
             writer,
             path,
+            records,
+            enc,
+            lock,
+            bg,
+            _codec: PhantomData,
         })
     }
 
@@ -231,32 +998,136 @@ where
     ///
     /// This will use the persistence strategy chosen for the instance of `Perds`
     pub fn insert(&mut self, k: K, v: V) -> Result<Option<V>, Error> {
-        let cmd = postcard::to_stdvec(&(Operation::Insert, &k, &v))?;
+        let cmd = frame(&C::encode_insert(&k, &v)?);
+        self.append(cmd)?;
+        self.records += 1;
+        // Update in memory DS after the record was written (or, under
+        // Strategy::Background, queued for the writer thread)
+        let prev = self.inner.insert(k, v);
+        self.maybe_compact()?;
+        Ok(prev)
+    }
+
+    /// Append a framed record to the log, encrypting it first when the instance
+    /// is in encrypted mode
+    ///
+    /// Under [Strategy::Background] the record is handed to the writer thread
+    /// without blocking on disk; otherwise it is written to the [BufWriter],
+    /// which is flushed unless [Strategy::Manual] was chosen.
+    fn append(&mut self, mut cmd: Vec<u8>) -> Result<(), Error> {
+        if let Some(c) = self.enc.as_mut() {
+            xchacha_apply(&c.key, &c.nonce, c.offset, &mut cmd);
+            c.offset += cmd.len() as u64;
+        }
+        if let Some(bg) = self.bg.as_ref() {
+            bg.enqueue(cmd);
+            return Ok(());
+        }
         self.writer.write_all(cmd.as_slice())?;
-        if let Strategy::Stream = self.strategy {
+        if !matches!(self.strategy, Strategy::Manual) {
             self.writer.flush()?;
         }
-        // Update in memory DS after successful disk write
-        Ok(self.inner.insert(k, v))
+        Ok(())
     }
 
     /// Remove a value from the `HashMap`
     ///
     /// This will use the persistence strategy chosen for the instance of `Perds`
     pub fn remove(&mut self, k: K) -> Result<Option<V>, Error> {
-        let cmd = postcard::to_stdvec(&(Operation::Delete, &k))?;
-        self.writer.write_all(cmd.as_slice())?;
-        if let Strategy::Stream = self.strategy {
-            self.writer.flush()?;
+        let cmd = frame(&C::encode_delete(&k)?);
+        self.append(cmd)?;
+        self.records += 1;
+        let prev = self.inner.remove(&k);
+        self.maybe_compact()?;
+        Ok(prev)
+    }
+
+    /// Snapshot the [Strategy::Background] writer counters, or [None] for other
+    /// strategies
+    pub fn background_stats(&self) -> Option<BackgroundStats> {
+        self.bg.as_ref().map(|bg| bg.stats())
+    }
+
+    /// Compact the append only log if the active [Strategy] calls for it
+    fn maybe_compact(&mut self) -> Result<(), Error> {
+        if let Strategy::Compact { max_records } = self.strategy {
+            if self.records > max_records {
+                self.compact()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Rewrite the append only log so it holds exactly one record per live entry
+    ///
+    /// The log is append only, so overwriting or removing a key leaves the old
+    /// records behind forever. This rewrites a fresh log containing a single
+    /// `Insert` per live entry into a sibling temp file, fsyncs it, then
+    /// atomically [renames](std::fs::rename) it over the backing file. The
+    /// writer is swapped to the compacted file positioned for further appends.
+    pub fn compact(&mut self) -> Result<(), Error> {
+        // Drain any background writer so its pending records are on disk before
+        // the log is rewritten
+        self.flush()?;
+        let tmp = self.path.with_extension("compact.tmp");
+        let mut offset = HEADER_LEN as u64;
+        {
+            let mut writer = BufWriter::new(
+                File::options()
+                    .write(true)
+                    .truncate(true)
+                    .create(true)
+                    .open(&tmp)?,
+            );
+            write_header(&mut writer, C::TAG, self.enc.is_some())?;
+            // Re-encrypt under the same key/nonce at the new, denser offsets
+            if let Some(c) = self.enc.as_ref() {
+                writer.write_all(&c.nonce)?;
+                offset += NONCE_LEN as u64;
+            }
+            for (k, v) in self.inner.iter() {
+                let mut cmd = frame(&C::encode_insert(k, v)?);
+                if let Some(c) = self.enc.as_ref() {
+                    xchacha_apply(&c.key, &c.nonce, offset, &mut cmd);
+                }
+                offset += cmd.len() as u64;
+                writer.write_all(cmd.as_slice())?;
+            }
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
         }
-        Ok(self.inner.remove(&k))
+        std::fs::rename(&tmp, &self.path)?;
+        // The rename retargets the directory entry to a fresh inode, so the
+        // advisory lock held in self.lock is now attached to the orphaned old
+        // inode rather than the file callers actually see at this path
+        self.lock = acquire_lock(&self.path, false, None)?;
+        let f = File::options().append(true).open(&self.path)?;
+        self.writer = BufWriter::new(f);
+        self.records = self.inner.len();
+        if let Some(c) = self.enc.as_mut() {
+            c.offset = offset;
+        }
+        if let Some(mut bg) = self.bg.take() {
+            // The writer thread's BufWriter still targets the inode the rename
+            // just unlinked; respawn it against the compacted file so appends
+            // made after compact() don't vanish into the orphaned file
+            bg.shutdown();
+            self.bg = maybe_spawn_bg(&self.strategy, &self.path)?;
+        }
+        Ok(())
     }
 
-    /// Flush the [BufWriter]
+    /// Flush pending writes to disk
     ///
-    /// If [Strategy::Manual] was chosen this function should
-    /// be called in order to ensure that the state changes were saved to disk
+    /// If [Strategy::Manual] was chosen this function should be called in order
+    /// to ensure that the state changes were saved to disk. Under
+    /// [Strategy::Background] it blocks until the writer thread has drained and
+    /// committed every queued record.
     pub fn flush(&mut self) -> Result<(), Error> {
+        if let Some(bg) = self.bg.as_ref() {
+            bg.flush();
+            return Ok(());
+        }
         Ok(self.writer.flush()?)
     }
 }
@@ -272,7 +1143,8 @@ mod tests {
         const TEST_FILE: &str = "./test/test.postcard";
 
         let path = PathBuf::from_str(TEST_FILE).unwrap();
-        let mut perds = Perds::new(HashMap::new(), Strategy::Stream, path.clone()).unwrap();
+        let mut perds =
+            Perds::<&str, &str>::new(HashMap::new(), Strategy::Stream, path.clone()).unwrap();
 
         let val = perds.insert("abc", "fed").unwrap();
         assert_eq!(val, None);
@@ -283,19 +1155,25 @@ mod tests {
 
         perds.insert("hello", "world").unwrap();
 
-        assert_eq!(
-            &[
-                0, 3, b'a', b'b', b'c', 3, b'f', b'e', b'd', 0, 3, b'a', b'b', b'c', 3, b'd', b'e',
-                b'f', 1, 3, b'a', b'b', b'c', 0, 5, b'h', b'e', b'l', b'l', b'o', 5, b'w', b'o',
-                b'r', b'l', b'd'
-            ],
-            std::fs::read(TEST_FILE).unwrap().as_slice()
-        );
+        let mut expected = Vec::new();
+        expected.extend_from_slice(&MAGIC);
+        expected.extend_from_slice(&[FORMAT_VERSION, Postcard::TAG]);
+        for payload in [
+            vec![0, 3, b'a', b'b', b'c', 3, b'f', b'e', b'd'],
+            vec![0, 3, b'a', b'b', b'c', 3, b'd', b'e', b'f'],
+            vec![1, 3, b'a', b'b', b'c'],
+            vec![0, 5, b'h', b'e', b'l', b'l', b'o', 5, b'w', b'o', b'r', b'l', b'd'],
+        ] {
+            expected.extend_from_slice(&frame(&payload));
+        }
+        assert_eq!(expected, std::fs::read(TEST_FILE).unwrap());
         drop(perds);
 
-        let perds =
-            Perds::from_file(Strategy::Stream, PathBuf::from_str(TEST_FILE).unwrap()).unwrap();
+        let (perds, report) =
+            Perds::<String, String>::from_file(Strategy::Stream, PathBuf::from_str(TEST_FILE).unwrap())
+                .unwrap();
 
+        assert_eq!(report.bytes_truncated, 0);
         assert_eq!(perds.get(&"hello".to_string()), Some(&"world".to_string()));
 
         std::fs::remove_file(&path).unwrap();
@@ -305,15 +1183,403 @@ mod tests {
     fn test_file_created() {
         let map = HashMap::from_iter([("foo", "bar")]);
         let path = PathBuf::from_str("./test/test_new.postcard").unwrap();
-        Perds::new(map, Strategy::Stream, path.clone()).unwrap();
+        Perds::<&str, &str>::new(map, Strategy::Stream, path.clone()).unwrap();
 
-        let perds = Perds::from_file(Strategy::Stream, path.clone()).unwrap();
+        let (perds, _) = Perds::<String, String>::from_file(Strategy::Stream, path.clone()).unwrap();
 
         assert_eq!(perds.get(&"foo".to_string()), Some(&"bar".to_string()));
 
         std::fs::remove_file(&path).unwrap();
     }
 
+    #[test]
+    fn test_compact() {
+        const TEST_FILE: &str = "./test/test_compact.postcard";
+
+        let path = PathBuf::from_str(TEST_FILE).unwrap();
+        let mut perds =
+            Perds::<&str, &str>::new(HashMap::new(), Strategy::Stream, path.clone()).unwrap();
+
+        // Overwrite the same key many times, leaving stale records behind
+        for _ in 0..10 {
+            perds.insert("abc", "def").unwrap();
+        }
+        perds.insert("hello", "world").unwrap();
+        assert_eq!(perds.records, 11);
+
+        perds.compact().unwrap();
+
+        // One record per live entry remains
+        assert_eq!(perds.records, 2);
+        drop(perds);
+
+        let (perds, _) = Perds::<String, String>::from_file(Strategy::Stream, path.clone()).unwrap();
+        assert_eq!(perds.get(&"abc".to_string()), Some(&"def".to_string()));
+        assert_eq!(perds.get(&"hello".to_string()), Some(&"world".to_string()));
+        assert_eq!(perds.records, 2);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_auto_compact() {
+        const TEST_FILE: &str = "./test/test_auto_compact.postcard";
+
+        let path = PathBuf::from_str(TEST_FILE).unwrap();
+        let strategy = Strategy::Compact { max_records: 4 };
+        let mut perds =
+            Perds::<&str, &str>::new(HashMap::new(), strategy, path.clone()).unwrap();
+
+        for _ in 0..10 {
+            perds.insert("abc", "def").unwrap();
+        }
+
+        // The log is kept bounded rather than growing to 10 records
+        assert!(perds.records <= 4);
+        assert_eq!(perds.get(&"abc"), Some(&"def"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_bad_magic() {
+        const TEST_FILE: &str = "./test/test_bad_magic.postcard";
+        // A bare headerless record stream as written by older versions
+        std::fs::write(TEST_FILE, [0, 3, b'a', b'b', b'c', 3, b'd', b'e', b'f']).unwrap();
+
+        let res = Perds::<String, String>::from_file(
+            Strategy::Stream,
+            PathBuf::from_str(TEST_FILE).unwrap(),
+        );
+        assert!(matches!(res, Err(Error::BadMagic)));
+
+        std::fs::remove_file(TEST_FILE).unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_legacy() {
+        const TEST_FILE: &str = "./test/test_upgrade.postcard";
+        let path = PathBuf::from_str(TEST_FILE).unwrap();
+        // A legacy headerless file: insert "abc" -> "def"
+        std::fs::write(&path, [0, 3, b'a', b'b', b'c', 3, b'd', b'e', b'f']).unwrap();
+
+        Perds::<String, String>::upgrade(&path).unwrap();
+
+        let (perds, _) =
+            Perds::<String, String>::from_file(Strategy::Stream, path.clone()).unwrap();
+        assert_eq!(perds.get(&"abc".to_string()), Some(&"def".to_string()));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_torn_tail_recovery() {
+        use std::io::Write as _;
+
+        const TEST_FILE: &str = "./test/test_torn.postcard";
+        let path = PathBuf::from_str(TEST_FILE).unwrap();
+
+        let mut perds =
+            Perds::<&str, &str>::new(HashMap::new(), Strategy::Stream, path.clone()).unwrap();
+        perds.insert("a", "1").unwrap();
+        perds.insert("b", "2").unwrap();
+        drop(perds);
+
+        // Simulate a crash mid-write: a frame header claiming 9 payload bytes
+        // but only 3 actually on disk
+        let mut f = File::options().append(true).open(&path).unwrap();
+        f.write_all(&9u32.to_le_bytes()).unwrap();
+        f.write_all(&0u32.to_le_bytes()).unwrap();
+        f.write_all(&[1, 2, 3]).unwrap();
+        drop(f);
+
+        let (perds, report) =
+            Perds::<String, String>::from_file(Strategy::Stream, path.clone()).unwrap();
+        assert_eq!(report.records_replayed, 2);
+        assert_eq!(report.bytes_truncated, 11);
+        assert_eq!(perds.get(&"a".to_string()), Some(&"1".to_string()));
+        assert_eq!(perds.get(&"b".to_string()), Some(&"2".to_string()));
+        drop(perds);
+
+        // The torn tail was healed, so a second load finds a clean log
+        let (_, report) =
+            Perds::<String, String>::from_file(Strategy::Stream, path.clone()).unwrap();
+        assert_eq!(report.bytes_truncated, 0);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_encrypted_roundtrip() {
+        const TEST_FILE: &str = "./test/test_encrypted.postcard";
+        let path = PathBuf::from_str(TEST_FILE).unwrap();
+        let key = [7u8; 32];
+
+        let mut perds = Perds::<&str, &str>::new_encrypted(
+            HashMap::new(),
+            Strategy::Stream,
+            path.clone(),
+            key,
+        )
+        .unwrap();
+        perds.insert("secret", "value").unwrap();
+        perds.insert("other", "thing").unwrap();
+        drop(perds);
+
+        // The plaintext value must not appear on disk
+        let raw = std::fs::read(TEST_FILE).unwrap();
+        assert!(!raw.windows(5).any(|w| w == b"value"));
+
+        let (perds, report) =
+            Perds::<String, String>::from_file_encrypted(Strategy::Stream, path.clone(), key)
+                .unwrap();
+        assert_eq!(report.bytes_truncated, 0);
+        assert_eq!(perds.get(&"secret".to_string()), Some(&"value".to_string()));
+        assert_eq!(perds.get(&"other".to_string()), Some(&"thing".to_string()));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_encrypted_append_stays_in_sync() {
+        const TEST_FILE: &str = "./test/test_encrypted_append.postcard";
+        let path = PathBuf::from_str(TEST_FILE).unwrap();
+        let key = [3u8; 32];
+
+        let mut perds = Perds::<&str, &str>::new_encrypted(
+            HashMap::new(),
+            Strategy::Stream,
+            path.clone(),
+            key,
+        )
+        .unwrap();
+        perds.insert("a", "1").unwrap();
+        drop(perds);
+
+        // Re-open and continue appending; the keystream must stay in sync
+        let (mut perds, _) =
+            Perds::<String, String>::from_file_encrypted(Strategy::Stream, path.clone(), key)
+                .unwrap();
+        perds.insert("b".to_string(), "2".to_string()).unwrap();
+        drop(perds);
+
+        let (perds, _) =
+            Perds::<String, String>::from_file_encrypted(Strategy::Stream, path.clone(), key)
+                .unwrap();
+        assert_eq!(perds.get(&"a".to_string()), Some(&"1".to_string()));
+        assert_eq!(perds.get(&"b".to_string()), Some(&"2".to_string()));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_wrong_mode_rejected() {
+        const TEST_FILE: &str = "./test/test_wrong_mode.postcard";
+        let path = PathBuf::from_str(TEST_FILE).unwrap();
+        let key = [9u8; 32];
+
+        let mut perds = Perds::<&str, &str>::new_encrypted(
+            HashMap::new(),
+            Strategy::Stream,
+            path.clone(),
+            key,
+        )
+        .unwrap();
+        perds.insert("abc", "def").unwrap();
+        drop(perds);
+
+        // Opening an encrypted file with the plaintext loader must be rejected
+        // instead of replaying ciphertext as records
+        let res = Perds::<String, String>::from_file(Strategy::Stream, path.clone());
+        assert!(matches!(res, Err(Error::WrongMode)));
+
+        std::fs::remove_file(&path).unwrap();
+
+        let mut perds =
+            Perds::<&str, &str>::new(HashMap::new(), Strategy::Stream, path.clone()).unwrap();
+        perds.insert("abc", "def").unwrap();
+        drop(perds);
+
+        // And the reverse: the encrypted loader must reject a plaintext file
+        let res = Perds::<String, String>::from_file_encrypted(Strategy::Stream, path.clone(), key);
+        assert!(matches!(res, Err(Error::WrongMode)));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_bincode_codec() {
+        const TEST_FILE: &str = "./test/test_bincode.postcard";
+        let path = PathBuf::from_str(TEST_FILE).unwrap();
+
+        let mut perds =
+            Perds::<&str, &str, Bincode>::new(HashMap::new(), Strategy::Stream, path.clone())
+                .unwrap();
+        perds.insert("abc", "def").unwrap();
+        perds.insert("hello", "world").unwrap();
+        drop(perds);
+
+        let (perds, _) =
+            Perds::<String, String, Bincode>::from_file(Strategy::Stream, path.clone()).unwrap();
+        assert_eq!(perds.get(&"abc".to_string()), Some(&"def".to_string()));
+        assert_eq!(perds.get(&"hello".to_string()), Some(&"world".to_string()));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_wrong_codec_rejected() {
+        const TEST_FILE: &str = "./test/test_wrong_codec.postcard";
+        let path = PathBuf::from_str(TEST_FILE).unwrap();
+
+        let mut perds =
+            Perds::<&str, &str>::new(HashMap::new(), Strategy::Stream, path.clone()).unwrap();
+        perds.insert("abc", "def").unwrap();
+        drop(perds);
+
+        // Opening a postcard file with the bincode codec must be rejected
+        let res =
+            Perds::<String, String, Bincode>::from_file(Strategy::Stream, path.clone());
+        assert!(matches!(res, Err(Error::UnsupportedCodec(0))));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_exclusive_lock() {
+        const TEST_FILE: &str = "./test/test_lock.postcard";
+        let path = PathBuf::from_str(TEST_FILE).unwrap();
+
+        let mut p1 =
+            Perds::<&str, &str>::new(HashMap::new(), Strategy::Stream, path.clone()).unwrap();
+        p1.insert("abc", "def").unwrap();
+
+        // A second opener of the same path must be rejected while p1 is alive
+        let second = Perds::<String, String>::from_file(Strategy::Stream, path.clone());
+        assert!(matches!(second, Err(Error::Locked)));
+
+        // Once the first holder is dropped the lock is released
+        drop(p1);
+        let (p2, _) = Perds::<String, String>::from_file(Strategy::Stream, path.clone()).unwrap();
+        assert_eq!(p2.get(&"abc".to_string()), Some(&"def".to_string()));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_lock_held_across_compact() {
+        const TEST_FILE: &str = "./test/test_lock_compact.postcard";
+        let path = PathBuf::from_str(TEST_FILE).unwrap();
+
+        let mut perds =
+            Perds::<&str, &str>::new(HashMap::new(), Strategy::Stream, path.clone()).unwrap();
+        perds.insert("abc", "def").unwrap();
+        perds.insert("abc", "def").unwrap();
+        perds.compact().unwrap();
+
+        // compact() rewrites the log via a rename onto a fresh inode; the lock
+        // must follow it so a second opener is still rejected afterwards
+        let second = Perds::<String, String>::from_file(Strategy::Stream, path.clone());
+        assert!(matches!(second, Err(Error::Locked)));
+
+        drop(perds);
+        let (third, _) = Perds::<String, String>::from_file(Strategy::Stream, path.clone()).unwrap();
+        assert_eq!(third.get(&"abc".to_string()), Some(&"def".to_string()));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_background_group_commit() {
+        use std::time::Duration;
+
+        const TEST_FILE: &str = "./test/test_background.postcard";
+        let path = PathBuf::from_str(TEST_FILE).unwrap();
+        let strategy = Strategy::Background {
+            max_batch: 2,
+            max_delay: Duration::from_millis(50),
+        };
+
+        let mut perds =
+            Perds::<&str, &str>::new(HashMap::new(), strategy, path.clone()).unwrap();
+        perds.insert("a", "1").unwrap();
+        perds.insert("b", "2").unwrap();
+        perds.insert("c", "3").unwrap();
+        perds.flush().unwrap();
+
+        let stats = perds.background_stats().unwrap();
+        assert_eq!(stats.queued, 3);
+        assert_eq!(stats.committed, 3);
+        drop(perds);
+
+        let (perds, _) =
+            Perds::<String, String>::from_file(Strategy::Stream, path.clone()).unwrap();
+        assert_eq!(perds.get(&"a".to_string()), Some(&"1".to_string()));
+        assert_eq!(perds.get(&"b".to_string()), Some(&"2".to_string()));
+        assert_eq!(perds.get(&"c".to_string()), Some(&"3".to_string()));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_background_drain_on_drop() {
+        use std::time::Duration;
+
+        const TEST_FILE: &str = "./test/test_background_drop.postcard";
+        let path = PathBuf::from_str(TEST_FILE).unwrap();
+        let strategy = Strategy::Background {
+            max_batch: 1024,
+            max_delay: Duration::from_secs(3600),
+        };
+
+        // Neither the batch nor the delay threshold is hit, so only the drain
+        // on Drop can persist these records
+        let mut perds =
+            Perds::<&str, &str>::new(HashMap::new(), strategy, path.clone()).unwrap();
+        perds.insert("a", "1").unwrap();
+        perds.insert("b", "2").unwrap();
+        drop(perds);
+
+        let (perds, _) =
+            Perds::<String, String>::from_file(Strategy::Stream, path.clone()).unwrap();
+        assert_eq!(perds.get(&"a".to_string()), Some(&"1".to_string()));
+        assert_eq!(perds.get(&"b".to_string()), Some(&"2".to_string()));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_compact_under_background_strategy() {
+        use std::time::Duration;
+
+        const TEST_FILE: &str = "./test/test_compact_background.postcard";
+        let path = PathBuf::from_str(TEST_FILE).unwrap();
+        let strategy = Strategy::Background {
+            max_batch: 1,
+            max_delay: Duration::from_millis(50),
+        };
+
+        let mut perds =
+            Perds::<&str, &str>::new(HashMap::new(), strategy, path.clone()).unwrap();
+        perds.insert("a", "1").unwrap();
+        perds.insert("b", "2").unwrap();
+        perds.compact().unwrap();
+
+        // The writer thread must be respawned against the compacted file, not
+        // left pointing at the inode the compaction rename just unlinked
+        perds.insert("c", "3").unwrap();
+        perds.flush().unwrap();
+        drop(perds);
+
+        let (perds, _) =
+            Perds::<String, String>::from_file(Strategy::Stream, path.clone()).unwrap();
+        assert_eq!(perds.get(&"a".to_string()), Some(&"1".to_string()));
+        assert_eq!(perds.get(&"b".to_string()), Some(&"2".to_string()));
+        assert_eq!(perds.get(&"c".to_string()), Some(&"3".to_string()));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
     #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
     struct Foo {
         x: i32,
@@ -326,11 +1592,13 @@ mod tests {
         let my_foo = Foo { x: 2, y: 3 };
 
         let path = PathBuf::from_str(TEST_STRUCT).unwrap();
-        let mut perds = Perds::new(HashMap::new(), Strategy::Stream, path.clone()).unwrap();
+        let mut perds =
+            Perds::<&str, Foo>::new(HashMap::new(), Strategy::Stream, path.clone()).unwrap();
         perds.insert("my_foo", my_foo.clone()).unwrap();
         drop(perds);
-        let perds =
-            Perds::from_file(Strategy::Stream, PathBuf::from_str(TEST_STRUCT).unwrap()).unwrap();
+        let (perds, _) =
+            Perds::<String, Foo>::from_file(Strategy::Stream, PathBuf::from_str(TEST_STRUCT).unwrap())
+                .unwrap();
 
         assert_eq!(perds.get(&"my_foo".to_string()), Some(&my_foo));
 